@@ -0,0 +1,366 @@
+// Copyright lowRISC contributors (OpenTitan project).
+// Licensed under the Apache License, Version 2.0, see LICENSE for details.
+// SPDX-License-Identifier: Apache-2.0
+
+// On-disk store of the provisioning station's certificate-endorsement authority, modeled on
+// the UEFI secure-variable key hierarchy (PK authorizes KEK updates, KEK authorizes CA/dbx
+// updates), so operators can rotate the endorsement CA and revoke compromised sub-CAs
+// offline, with rollback protection, instead of re-flashing every station.
+
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, ensure, Context, Result};
+use p256::ecdsa::signature::Verifier;
+use p256::ecdsa::{Signature, VerifyingKey};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+/// A slot in the authority hierarchy that an `AuthHeader` update targets.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CaSlot {
+    /// The root Platform Key. Authorizes updates to `Kek`. Always holds exactly one key.
+    Pk,
+    /// A Key-Exchange Key. Authorizes updates to `Ca` and `Dbx`.
+    Kek,
+    /// The active CA certificate used to endorse on-device certs.
+    Ca,
+    /// The denylist of revoked certificate/CA public key SHA-256 hashes.
+    Dbx,
+}
+
+/// Either replace the slot's contents (`Pk`/`Ca`, which hold a single value) or add/remove
+/// entries from it (`Kek`/`Dbx`, which hold sets).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum UpdateOp {
+    Append(Vec<Vec<u8>>),
+    Delete(Vec<Vec<u8>>),
+}
+
+/// A signed rollover/revocation update, analogous to a UEFI Authentication Header: a payload
+/// (new CA cert, new KEK, or new dbx entries) together with a monotonic timestamp and a
+/// signature over both, checked against the key currently authorized for `slot`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuthHeader {
+    pub slot: CaSlot,
+    pub timestamp: u64,
+    pub op: UpdateOp,
+    /// ECDSA/P-256 signature over `to_be_signed()`, from the authorizing key.
+    pub signature: Vec<u8>,
+}
+
+impl AuthHeader {
+    fn to_be_signed(&self) -> Vec<u8> {
+        let mut bytes = vec![match self.slot {
+            CaSlot::Pk => 0,
+            CaSlot::Kek => 1,
+            CaSlot::Ca => 2,
+            CaSlot::Dbx => 3,
+        }];
+        bytes.extend_from_slice(&self.timestamp.to_be_bytes());
+        let entries = match &self.op {
+            UpdateOp::Append(entries) => {
+                bytes.push(0);
+                entries
+            }
+            UpdateOp::Delete(entries) => {
+                bytes.push(1);
+                entries
+            }
+        };
+        for entry in entries {
+            bytes.extend_from_slice(&(entry.len() as u32).to_be_bytes());
+            bytes.extend_from_slice(entry);
+        }
+        bytes
+    }
+}
+
+/// The authority state persisted on disk: the root Platform Key, the set of Key-Exchange
+/// Keys it has authorized, the active CA certificate, the denylist, and a rollback counter
+/// per slot. Each slot tracks its own monotonic timestamp (as in the UEFI model this is
+/// based on) rather than sharing one counter, so e.g. rotating the CA to a high timestamp
+/// cannot later block an urgent `dbx` revocation signed with an earlier, still-unused
+/// timestamp.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CaStore {
+    pk: Vec<u8>,
+    kek: Vec<Vec<u8>>,
+    ca_certificate: Vec<u8>,
+    dbx: HashSet<[u8; 32]>,
+    pk_timestamp: u64,
+    kek_timestamp: u64,
+    ca_timestamp: u64,
+    dbx_timestamp: u64,
+}
+
+impl CaStore {
+    pub fn load(path: &Path) -> Result<Self> {
+        let data = fs::read(path)
+            .with_context(|| format!("failed to read CA store {}", path.display()))?;
+        serde_json::from_slice(&data)
+            .with_context(|| format!("failed to parse CA store {}", path.display()))
+    }
+
+    fn save(&self, path: &Path) -> Result<()> {
+        // Write to a sibling temp file and rename, so a crash mid-write cannot corrupt the
+        // store that guards rollback protection.
+        let tmp_path = path.with_extension("tmp");
+        fs::write(&tmp_path, serde_json::to_vec_pretty(self)?)?;
+        fs::rename(&tmp_path, path)?;
+        Ok(())
+    }
+
+    /// The CA certificate (DER) currently used to endorse on-device certs.
+    pub fn active_ca_certificate(&self) -> &[u8] {
+        &self.ca_certificate
+    }
+
+    pub fn is_denylisted(&self, cert_or_key_hash: &[u8; 32]) -> bool {
+        self.dbx.contains(cert_or_key_hash)
+    }
+
+    fn authorized_keys(&self, slot: CaSlot) -> Vec<&[u8]> {
+        match slot {
+            CaSlot::Pk => vec![&self.pk],
+            CaSlot::Kek => vec![&self.pk],
+            CaSlot::Ca | CaSlot::Dbx => self.kek.iter().map(Vec::as_slice).collect(),
+        }
+    }
+
+    fn timestamp(&self, slot: CaSlot) -> u64 {
+        match slot {
+            CaSlot::Pk => self.pk_timestamp,
+            CaSlot::Kek => self.kek_timestamp,
+            CaSlot::Ca => self.ca_timestamp,
+            CaSlot::Dbx => self.dbx_timestamp,
+        }
+    }
+
+    fn set_timestamp(&mut self, slot: CaSlot, value: u64) {
+        match slot {
+            CaSlot::Pk => self.pk_timestamp = value,
+            CaSlot::Kek => self.kek_timestamp = value,
+            CaSlot::Ca => self.ca_timestamp = value,
+            CaSlot::Dbx => self.dbx_timestamp = value,
+        }
+    }
+
+    fn verify_signature(&self, header: &AuthHeader) -> Result<()> {
+        let to_be_signed = header.to_be_signed();
+        let signature = Signature::from_slice(&header.signature)
+            .context("malformed AuthHeader signature")?;
+        let authorized = self.authorized_keys(header.slot);
+        for key_bytes in authorized {
+            if let Ok(key) = VerifyingKey::from_sec1_bytes(key_bytes) {
+                if key.verify(&to_be_signed, &signature).is_ok() {
+                    return Ok(());
+                }
+            }
+        }
+        bail!(
+            "AuthHeader for slot {:?} is not signed by a key currently authorized for that slot",
+            header.slot
+        )
+    }
+
+    /// Verifies `header`'s signature against the key(s) currently authorized for its slot,
+    /// enforces rollback protection (the embedded timestamp must be strictly greater than the
+    /// slot's own stored timestamp), applies the add/delete atomically, and persists the
+    /// result to `path`.
+    pub fn apply_update(&mut self, path: &Path, header: AuthHeader) -> Result<()> {
+        let current_timestamp = self.timestamp(header.slot);
+        ensure!(
+            header.timestamp > current_timestamp,
+            "rollback protection: update timestamp {} for slot {:?} is not greater than its \
+             current timestamp {}",
+            header.timestamp,
+            header.slot,
+            current_timestamp
+        );
+        self.verify_signature(&header)?;
+
+        match (header.slot, &header.op) {
+            (CaSlot::Pk, UpdateOp::Append(entries)) => {
+                ensure!(entries.len() == 1, "PK slot must hold exactly one key");
+                self.pk = entries[0].clone();
+            }
+            (CaSlot::Pk, UpdateOp::Delete(_)) => bail!("PK slot must hold exactly one key"),
+            (CaSlot::Ca, UpdateOp::Append(entries)) => {
+                ensure!(entries.len() == 1, "Ca slot must hold exactly one certificate");
+                self.ca_certificate = entries[0].clone();
+            }
+            (CaSlot::Ca, UpdateOp::Delete(_)) => bail!("Ca slot must hold exactly one certificate"),
+            (CaSlot::Kek, UpdateOp::Append(entries)) => {
+                for entry in entries {
+                    if !self.kek.contains(entry) {
+                        self.kek.push(entry.clone());
+                    }
+                }
+            }
+            (CaSlot::Kek, UpdateOp::Delete(entries)) => {
+                self.kek.retain(|k| !entries.contains(k));
+            }
+            (CaSlot::Dbx, UpdateOp::Append(entries)) => {
+                for entry in entries {
+                    self.dbx.insert(hash_entry(entry)?);
+                }
+            }
+            (CaSlot::Dbx, UpdateOp::Delete(entries)) => {
+                for entry in entries {
+                    self.dbx.remove(&hash_entry(entry)?);
+                }
+            }
+        }
+        self.set_timestamp(header.slot, header.timestamp);
+        self.save(path)
+    }
+}
+
+fn hash_entry(entry: &[u8]) -> Result<[u8; 32]> {
+    // `dbx` entries are stored pre-hashed (SHA-256 digests), but updates may carry either the
+    // 32-byte digest directly or the full cert/key bytes to hash.
+    if entry.len() == 32 {
+        let mut hash = [0u8; 32];
+        hash.copy_from_slice(entry);
+        Ok(hash)
+    } else {
+        Ok(Sha256::digest(entry).into())
+    }
+}
+
+/// Writes `bytes` to a temp file named after the current process id, so callers needing a
+/// filesystem path (e.g. OpenSSL chain validation) can point at the CA store's active
+/// certificate. The file is overwritten on each call and is not removed afterwards.
+pub fn write_temp_ca_certificate(bytes: &[u8]) -> Result<PathBuf> {
+    let path = std::env::temp_dir().join(format!("ft_ca_cert_{}.der", std::process::id()));
+    fs::write(&path, bytes)?;
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use p256::ecdsa::{signature::Signer, SigningKey};
+    use rand_core::OsRng;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    // Each test gets its own path, since `apply_update` persists to disk.
+    fn temp_store_path() -> PathBuf {
+        static COUNTER: AtomicU32 = AtomicU32::new(0);
+        std::env::temp_dir().join(format!(
+            "ft_ca_store_test_{}_{}.json",
+            std::process::id(),
+            COUNTER.fetch_add(1, Ordering::Relaxed)
+        ))
+    }
+
+    fn sign(key: &SigningKey, header: &mut AuthHeader) {
+        let signature: Signature = key.sign(&header.to_be_signed());
+        header.signature = signature.to_bytes().to_vec();
+    }
+
+    // A store with a known PK signing key and one authorized KEK signing key, so tests can
+    // produce validly-signed `AuthHeader`s for every slot.
+    fn test_store() -> (CaStore, SigningKey, SigningKey) {
+        let pk_key = SigningKey::random(&mut OsRng);
+        let kek_key = SigningKey::random(&mut OsRng);
+        let store = CaStore {
+            pk: VerifyingKey::from(&pk_key).to_sec1_bytes().to_vec(),
+            kek: vec![VerifyingKey::from(&kek_key).to_sec1_bytes().to_vec()],
+            ca_certificate: vec![0xCA; 4],
+            dbx: HashSet::new(),
+            pk_timestamp: 0,
+            kek_timestamp: 0,
+            ca_timestamp: 0,
+            dbx_timestamp: 0,
+        };
+        (store, pk_key, kek_key)
+    }
+
+    #[test]
+    fn rejects_update_with_non_increasing_timestamp() {
+        let (mut store, _pk_key, kek_key) = test_store();
+        let path = temp_store_path();
+
+        let mut header = AuthHeader {
+            slot: CaSlot::Ca,
+            timestamp: 5,
+            op: UpdateOp::Append(vec![vec![0xCB; 4]]),
+            signature: Vec::new(),
+        };
+        sign(&kek_key, &mut header);
+        store.apply_update(&path, header).unwrap();
+
+        // Same timestamp as the update just applied: must be rejected.
+        let mut replay = AuthHeader {
+            slot: CaSlot::Ca,
+            timestamp: 5,
+            op: UpdateOp::Append(vec![vec![0xCC; 4]]),
+            signature: Vec::new(),
+        };
+        sign(&kek_key, &mut replay);
+        assert!(store.apply_update(&path, replay).is_err());
+        // The rejected update must not have taken effect.
+        assert_eq!(store.active_ca_certificate(), &[0xCB; 4]);
+
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn per_slot_timestamps_are_independent() {
+        let (mut store, _pk_key, kek_key) = test_store();
+        let path = temp_store_path();
+
+        // Advance the `Ca` slot's timestamp far ahead of `Dbx`'s.
+        let mut ca_update = AuthHeader {
+            slot: CaSlot::Ca,
+            timestamp: 100,
+            op: UpdateOp::Append(vec![vec![0xCB; 4]]),
+            signature: Vec::new(),
+        };
+        sign(&kek_key, &mut ca_update);
+        store.apply_update(&path, ca_update).unwrap();
+
+        // A `Dbx` update with a much lower timestamp must still be accepted: each slot tracks
+        // its own rollback counter rather than sharing one.
+        let mut dbx_update = AuthHeader {
+            slot: CaSlot::Dbx,
+            timestamp: 1,
+            op: UpdateOp::Append(vec![vec![0xDD; 32]]),
+            signature: Vec::new(),
+        };
+        sign(&kek_key, &mut dbx_update);
+        store.apply_update(&path, dbx_update).unwrap();
+
+        assert!(store.is_denylisted(&[0xDD; 32]));
+
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn dbx_matches_both_prehashed_and_raw_entries() {
+        let (mut store, _pk_key, kek_key) = test_store();
+        let path = temp_store_path();
+
+        let prehashed = [0x11; 32];
+        let raw_entry = b"a certificate or public key longer than 32 bytes".to_vec();
+        let raw_hash: [u8; 32] = Sha256::digest(&raw_entry).into();
+
+        let mut header = AuthHeader {
+            slot: CaSlot::Dbx,
+            timestamp: 1,
+            op: UpdateOp::Append(vec![prehashed.to_vec(), raw_entry]),
+            signature: Vec::new(),
+        };
+        sign(&kek_key, &mut header);
+        store.apply_update(&path, header).unwrap();
+
+        assert!(store.is_denylisted(&prehashed));
+        assert!(store.is_denylisted(&raw_hash));
+        assert!(!store.is_denylisted(&[0x22; 32]));
+
+        let _ = fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,100 @@
+// Copyright lowRISC contributors (OpenTitan project).
+// Licensed under the Apache License, Version 2.0, see LICENSE for details.
+// SPDX-License-Identifier: Apache-2.0
+
+// Pluggable X.509 parsing/chain-validation backends, so `provision_certificates` is not
+// hard-wired to OpenSSL. A smaller embedded TLS stack (mbedTLS) is preferable on
+// constrained or differently-licensed build environments, and running both over the same
+// endorsed certs is a useful differential check: an encoding issue that one parser is lax
+// about and the other rejects is exactly the kind of bug that should be caught on the host,
+// not in silicon.
+
+use anyhow::{Context, Result};
+
+use cert_lib::{validate_certs_chain, HostEndorsedCert};
+use ot_certs::x509::parse_certificate;
+
+/// The result of a successful parse: DER that the selected backend (for `Differential`, both
+/// backends) accepted as well-formed; callers that need backend-specific details should go
+/// through the concrete validator directly.
+pub struct ParsedCert {
+    pub der: Vec<u8>,
+}
+
+pub trait CertValidator {
+    fn parse(&self, cert: &[u8]) -> Result<ParsedCert>;
+    fn validate_chain(&self, ca_certificate: &str, certs: &[HostEndorsedCert]) -> Result<()>;
+}
+
+pub struct OpenSslValidator;
+
+impl CertValidator for OpenSslValidator {
+    fn parse(&self, cert: &[u8]) -> Result<ParsedCert> {
+        let _ = parse_certificate(cert)?;
+        Ok(ParsedCert { der: cert.to_vec() })
+    }
+
+    fn validate_chain(&self, ca_certificate: &str, certs: &[HostEndorsedCert]) -> Result<()> {
+        validate_certs_chain(ca_certificate, certs)
+    }
+}
+
+pub struct MbedTlsValidator;
+
+impl CertValidator for MbedTlsValidator {
+    fn parse(&self, cert: &[u8]) -> Result<ParsedCert> {
+        mbedtls::x509::Certificate::from_der(cert).context("mbedTLS rejected certificate")?;
+        Ok(ParsedCert { der: cert.to_vec() })
+    }
+
+    fn validate_chain(&self, ca_certificate: &str, certs: &[HostEndorsedCert]) -> Result<()> {
+        let ca_der = std::fs::read(ca_certificate)
+            .with_context(|| format!("failed to read CA certificate {ca_certificate}"))?;
+        let ca = mbedtls::x509::Certificate::from_der(&ca_der)
+            .context("mbedTLS rejected CA certificate")?;
+        // Every cert handed in here was endorsed on the host with the CA's own key, so each
+        // one is verified directly against the CA certificate.
+        for cert in certs {
+            let subject = mbedtls::x509::Certificate::from_der(&cert.bytes)
+                .context("mbedTLS rejected endorsed certificate")?;
+            subject
+                .verify_signed_by(&ca)
+                .context("mbedTLS chain validation failed")?;
+        }
+        Ok(())
+    }
+}
+
+/// Selects which backend(s) `provision_certificates` validates endorsed certs with.
+pub enum CertValidatorBackend {
+    OpenSsl(OpenSslValidator),
+    MbedTls(MbedTlsValidator),
+    /// Runs both backends and requires both to accept, as a differential check that an
+    /// endorsed cert is well-formed under independent parsers.
+    Differential(OpenSslValidator, MbedTlsValidator),
+}
+
+impl CertValidator for CertValidatorBackend {
+    fn parse(&self, cert: &[u8]) -> Result<ParsedCert> {
+        match self {
+            Self::OpenSsl(validator) => validator.parse(cert),
+            Self::MbedTls(validator) => validator.parse(cert),
+            Self::Differential(openssl, mbedtls) => {
+                let parsed = openssl.parse(cert)?;
+                mbedtls.parse(cert)?;
+                Ok(parsed)
+            }
+        }
+    }
+
+    fn validate_chain(&self, ca_certificate: &str, certs: &[HostEndorsedCert]) -> Result<()> {
+        match self {
+            Self::OpenSsl(validator) => validator.validate_chain(ca_certificate, certs),
+            Self::MbedTls(validator) => validator.validate_chain(ca_certificate, certs),
+            Self::Differential(openssl, mbedtls) => {
+                openssl.validate_chain(ca_certificate, certs)?;
+                mbedtls.validate_chain(ca_certificate, certs)
+            }
+        }
+    }
+}
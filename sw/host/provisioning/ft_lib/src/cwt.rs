@@ -0,0 +1,274 @@
+// Copyright lowRISC contributors (OpenTitan project).
+// Licensed under the Apache License, Version 2.0, see LICENSE for details.
+// SPDX-License-Identifier: Apache-2.0
+
+// Minimal CWT (CBOR Web Token) / COSE_Sign1 support for the Open Profile for DICE
+// certificate endorsement path. This mirrors `parse_and_endorse_x509_cert`, but for
+// devices that export their DICE attestation certs as signed CWTs instead of X.509.
+
+use anyhow::{bail, Result};
+use ciborium::value::Value;
+use p256::ecdsa::signature::{Signer, Verifier};
+use p256::ecdsa::{Signature, SigningKey, VerifyingKey};
+
+use cert_lib::CertEndorsementKey;
+
+use crate::pkcs11_signer::Pkcs11Session;
+
+// COSE algorithm identifier for ECDSA w/ SHA-256 (ES256), see RFC 8152 Table 5.
+const COSE_ALG_ES256: i64 = -7;
+// COSE protected header label for the algorithm.
+const COSE_HEADER_ALG: i64 = 1;
+
+// Open Profile for DICE claim keys (draft-ietf-rats-dice-conceptual-profiles).
+const CWT_CLAIM_ISSUER: i64 = 1;
+const CWT_CLAIM_SUBJECT: i64 = 2;
+const CWT_CLAIM_CODE_HASH: i64 = -4670545;
+const CWT_CLAIM_CONFIGURATION_DESCRIPTOR: i64 = -4670548;
+const CWT_CLAIM_AUTHORITY_HASH: i64 = -4670549;
+
+fn cbor_encode(value: &Value) -> Result<Vec<u8>> {
+    let mut bytes = Vec::new();
+    ciborium::ser::into_writer(value, &mut bytes)?;
+    Ok(bytes)
+}
+
+// Builds the `Sig_structure` over which a COSE_Sign1 signature is computed, per RFC 8152
+// section 4.4: `["Signature1", protected_header, external_aad, payload]`, with an empty
+// `external_aad`.
+fn sig_structure(protected_header: &[u8], payload: &[u8]) -> Result<Vec<u8>> {
+    cbor_encode(&Value::Array(vec![
+        Value::Text("Signature1".into()),
+        Value::Bytes(protected_header.to_vec()),
+        Value::Bytes(Vec::new()),
+        Value::Bytes(payload.to_vec()),
+    ]))
+}
+
+fn verifying_key_for(key: &CertEndorsementKey) -> Result<VerifyingKey> {
+    match key {
+        CertEndorsementKey::LocalKey(secret_key) => {
+            Ok(VerifyingKey::from(secret_key.public_key()))
+        }
+        CertEndorsementKey::CkmsKey(_) => {
+            bail!("CWT/COSE verification against Cloud KMS keys is not yet supported")
+        }
+        CertEndorsementKey::Pkcs11Key { .. } => {
+            bail!("CWT/COSE verification against PKCS#11 keys is not yet supported")
+        }
+    }
+}
+
+// Sanity-checks that `tbs` decodes to a CWT claims map containing the claims the DICE
+// profile requires, without otherwise interpreting them (the device has already produced a
+// well-formed TBS payload; we only need to sign over it).
+fn check_cwt_claims(tbs: &[u8]) -> Result<()> {
+    let claims: Value = ciborium::de::from_reader(tbs)?;
+    let Value::Map(entries) = claims else {
+        bail!("CWT TBS payload is not a CBOR map");
+    };
+    for (label, name) in [
+        (CWT_CLAIM_ISSUER, "issuer"),
+        (CWT_CLAIM_SUBJECT, "subject"),
+        (CWT_CLAIM_CODE_HASH, "codeHash"),
+        (CWT_CLAIM_CONFIGURATION_DESCRIPTOR, "configurationDescriptor"),
+        (CWT_CLAIM_AUTHORITY_HASH, "authorityHash"),
+    ] {
+        let present = entries
+            .iter()
+            .any(|(k, _)| matches!(k, Value::Integer(i) if i128::from(*i) == label as i128));
+        if !present {
+            bail!("CWT TBS payload is missing required DICE claim `{name}`");
+        }
+    }
+    Ok(())
+}
+
+// Signs and verifies CWT/COSE certs on behalf of a single `CertEndorsementKey` for the
+// duration of one provisioning run. When the key is a PKCS#11 token key, the token session
+// is opened and logged into once, here, and reused for every CWT cert signed through it
+// instead of being re-opened per signature (re-initializing the module and logging in again
+// for every TBS cert is needless round-tripping with the HSM and risks
+// `CKR_CRYPTOKI_ALREADY_INITIALIZED` / re-login errors on real hardware). This covers only
+// the CBOR/CWT path; the X.509 path's PKCS#11 session (inside `cert_lib`'s
+// `parse_and_endorse_x509_cert`) is separate and not yet reused across calls.
+// Signing works with local and PKCS#11 keys, verification (`verify_cwt_cert`) with local
+// keys only; every other combination returns an error.
+pub struct CwtSigner<'a> {
+    key: &'a CertEndorsementKey,
+    pkcs11_session: Option<Pkcs11Session>,
+}
+
+impl<'a> CwtSigner<'a> {
+    pub fn new(key: &'a CertEndorsementKey) -> Result<Self> {
+        let pkcs11_session = match key {
+            CertEndorsementKey::Pkcs11Key {
+                module,
+                slot_or_token_label,
+                key_label,
+                pin,
+            } => Some(Pkcs11Session::open(
+                module,
+                slot_or_token_label,
+                key_label,
+                pin.as_deref(),
+            )?),
+            CertEndorsementKey::LocalKey(_) | CertEndorsementKey::CkmsKey(_) => None,
+        };
+        Ok(Self { key, pkcs11_session })
+    }
+
+    fn sign(&self, message: &[u8]) -> Result<Vec<u8>> {
+        match self.key {
+            CertEndorsementKey::LocalKey(secret_key) => {
+                let signing_key = SigningKey::from(secret_key.clone());
+                let signature: Signature = signing_key.sign(message);
+                Ok(signature.to_bytes().to_vec())
+            }
+            CertEndorsementKey::CkmsKey(_) => {
+                bail!("CWT/COSE endorsement via Cloud KMS keys is not yet supported")
+            }
+            CertEndorsementKey::Pkcs11Key { .. } => self
+                .pkcs11_session
+                .as_ref()
+                .expect("pkcs11 session is opened in `new` whenever `key` is a Pkcs11Key")
+                .sign(message),
+        }
+    }
+
+    // Endorses a CWT TBS payload by wrapping it in a COSE_Sign1 structure signed with `key`,
+    // analogous to `parse_and_endorse_x509_cert` for the X.509 path.
+    pub fn parse_and_endorse_cwt_cert(&self, tbs: Vec<u8>) -> Result<Vec<u8>> {
+        check_cwt_claims(&tbs)?;
+
+        let protected_header = cbor_encode(&Value::Map(vec![(
+            Value::Integer(COSE_HEADER_ALG.into()),
+            Value::Integer(COSE_ALG_ES256.into()),
+        )]))?;
+
+        let to_be_signed = sig_structure(&protected_header, &tbs)?;
+        let signature = self.sign(&to_be_signed)?;
+
+        cbor_encode(&Value::Array(vec![
+            Value::Bytes(protected_header),
+            Value::Map(Vec::new()),
+            Value::Bytes(tbs),
+            Value::Bytes(signature),
+        ]))
+    }
+
+    // Verifies a COSE_Sign1-wrapped CWT cert against the CA public key backing `key`. Only
+    // valid for certs that were just host-endorsed with this same `key` in this run (e.g. the
+    // UDS cert); certs endorsed on-device by a different, lower DICE layer key must instead be
+    // checked with `check_cose_sign1_shape`.
+    pub fn verify_cwt_cert(&self, cert: &[u8]) -> Result<()> {
+        let (protected_header, payload, signature) = parse_cose_sign1(cert)?;
+
+        let to_be_signed = sig_structure(&protected_header, &payload)?;
+        let verifying_key = verifying_key_for(self.key)?;
+        let signature = Signature::from_slice(&signature)?;
+        verifying_key
+            .verify(&to_be_signed, &signature)
+            .map_err(|e| anyhow::anyhow!("COSE_Sign1 signature verification failed: {e}"))
+    }
+}
+
+// Decodes a COSE_Sign1 structure into its (protected_header, payload, signature) fields,
+// checking only that it has the expected 4-element shape.
+fn parse_cose_sign1(cert: &[u8]) -> Result<(Vec<u8>, Vec<u8>, Vec<u8>)> {
+    let cose: Value = ciborium::de::from_reader(cert)?;
+    let Value::Array(elements) = cose else {
+        bail!("COSE_Sign1 cert is not a CBOR array");
+    };
+    let [Value::Bytes(protected_header), _unprotected, Value::Bytes(payload), Value::Bytes(signature)] =
+        elements.as_slice()
+    else {
+        bail!("COSE_Sign1 cert does not have the expected 4-element structure");
+    };
+    Ok((protected_header.clone(), payload.clone(), signature.clone()))
+}
+
+// Structural-only check that `cert` is a well-formed COSE_Sign1 CBOR structure, without
+// checking who signed it. Used for CWT certs that were already endorsed on-device by a
+// lower DICE layer's own key (not the host's `CertEndorsementKey`), mirroring the
+// structural-only `cert_validator.parse()` check done for already-endorsed X.509 certs.
+pub fn check_cose_sign1_shape(cert: &[u8]) -> Result<()> {
+    parse_cose_sign1(cert)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use p256::SecretKey;
+    use rand_core::OsRng;
+
+    fn test_tbs() -> Vec<u8> {
+        cbor_encode(&Value::Map(vec![
+            (Value::Integer(CWT_CLAIM_ISSUER.into()), Value::Text("test-issuer".into())),
+            (Value::Integer(CWT_CLAIM_SUBJECT.into()), Value::Text("test-subject".into())),
+            (Value::Integer(CWT_CLAIM_CODE_HASH.into()), Value::Bytes(vec![0xAA; 32])),
+            (
+                Value::Integer(CWT_CLAIM_CONFIGURATION_DESCRIPTOR.into()),
+                Value::Bytes(vec![0xBB; 16]),
+            ),
+            (Value::Integer(CWT_CLAIM_AUTHORITY_HASH.into()), Value::Bytes(vec![0xCC; 32])),
+        ]))
+        .unwrap()
+    }
+
+    #[test]
+    fn round_trips_endorse_and_verify() {
+        let key = CertEndorsementKey::LocalKey(SecretKey::random(&mut OsRng));
+        let signer = CwtSigner::new(&key).unwrap();
+
+        let cert = signer.parse_and_endorse_cwt_cert(test_tbs()).unwrap();
+
+        signer.verify_cwt_cert(&cert).unwrap();
+    }
+
+    #[test]
+    fn rejects_cert_endorsed_by_a_different_key() {
+        let key = CertEndorsementKey::LocalKey(SecretKey::random(&mut OsRng));
+        let signer = CwtSigner::new(&key).unwrap();
+        let cert = signer.parse_and_endorse_cwt_cert(test_tbs()).unwrap();
+
+        let other_key = CertEndorsementKey::LocalKey(SecretKey::random(&mut OsRng));
+        let other_signer = CwtSigner::new(&other_key).unwrap();
+
+        assert!(other_signer.verify_cwt_cert(&cert).is_err());
+    }
+
+    #[test]
+    fn rejects_tampered_payload() {
+        let key = CertEndorsementKey::LocalKey(SecretKey::random(&mut OsRng));
+        let signer = CwtSigner::new(&key).unwrap();
+        let cert = signer.parse_and_endorse_cwt_cert(test_tbs()).unwrap();
+
+        let (protected_header, mut payload, signature) = parse_cose_sign1(&cert).unwrap();
+        payload.push(0);
+        let tampered = cbor_encode(&Value::Array(vec![
+            Value::Bytes(protected_header),
+            Value::Map(Vec::new()),
+            Value::Bytes(payload),
+            Value::Bytes(signature),
+        ]))
+        .unwrap();
+
+        assert!(signer.verify_cwt_cert(&tampered).is_err());
+    }
+
+    #[test]
+    fn rejects_tbs_missing_a_required_claim() {
+        let tbs = cbor_encode(&Value::Map(vec![(
+            Value::Integer(CWT_CLAIM_ISSUER.into()),
+            Value::Text("test-issuer".into()),
+        )]))
+        .unwrap();
+
+        let key = CertEndorsementKey::LocalKey(SecretKey::random(&mut OsRng));
+        let signer = CwtSigner::new(&key).unwrap();
+
+        assert!(signer.parse_and_endorse_cwt_cert(tbs).is_err());
+    }
+}
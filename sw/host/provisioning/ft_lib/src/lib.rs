@@ -13,10 +13,10 @@ use elliptic_curve::SecretKey;
 use p256::NistP256;
 use zerocopy::AsBytes;
 
-use cert_lib::{
-    get_cert_size, parse_and_endorse_x509_cert, validate_certs_chain, CertEndorsementKey,
-    HostEndorsedCert,
-};
+use ca_store::{write_temp_ca_certificate, CaStore};
+use cert_lib::{get_cert_size, parse_and_endorse_x509_cert, CertEndorsementKey, HostEndorsedCert};
+use cert_validator::{CertValidator, CertValidatorBackend};
+use cwt::{check_cose_sign1_shape, CwtSigner};
 use ft_ext_lib::ft_ext;
 use opentitanlib::app::TransportWrapper;
 use opentitanlib::console::spi::SpiConsoleDevice;
@@ -29,7 +29,6 @@ use opentitanlib::test_utils::load_sram_program::{
 };
 use opentitanlib::test_utils::rpc::{ConsoleRecv, ConsoleSend};
 use opentitanlib::uart::console::UartConsole;
-use ot_certs::x509::parse_certificate;
 use ot_certs::CertFormat;
 use perso_tlv_lib::perso_tlv_get_field;
 use perso_tlv_lib::{CertHeader, CertHeaderType, ObjHeader, ObjHeaderType, ObjType};
@@ -38,6 +37,11 @@ use ujson_lib::provisioning_data::{
 };
 use util_lib::hash_lc_token;
 
+mod ca_store;
+mod cert_validator;
+mod cwt;
+mod pkcs11_signer;
+
 pub fn test_unlock(
     transport: &TransportWrapper,
     jtag_params: &JtagParams,
@@ -162,13 +166,21 @@ pub fn test_exit(
     Ok(())
 }
 
-// This enum provides two different certificate signing key representations. In
+// This enum provides three different certificate signing key representations. In
 // case the local fake certificate is used for certificate chain validation, the
 // key is a path to the file containing the private key. In case a Cloud KMS
-// certificate is used, the key is a string, the ID of the key in cloud storage.
+// certificate is used, the key is a string, the ID of the key in cloud storage. In
+// case a PKCS#11 token (e.g. an HSM) holds the key, it is identified by the token
+// and key labels the token was provisioned with; the key material never leaves it.
 pub enum KeyWrapper {
     LocalKey(PathBuf),
     CkmsKey(String),
+    Pkcs11Key {
+        module: PathBuf,
+        slot_or_token_label: String,
+        key_label: String,
+        pin: Option<String>,
+    },
 }
 
 fn send_rma_unlock_token_hash(
@@ -262,6 +274,7 @@ fn get_cert(data: &[u8]) -> Result<CertHeader> {
 fn push_endorsed_cert(
     cert: &Vec<u8>,
     ref_cert: &CertHeader,
+    format: CertFormat,
     output: &mut ArrayVec<u8, 4096>,
 ) -> Result<()> {
     // Need to wrap the new cert in CertHeader
@@ -270,7 +283,11 @@ fn push_endorsed_cert(
         + ref_cert.cert_name.len()
         + cert.len();
 
-    let obj_header = perso_tlv_lib::make_obj_header(total_size, ObjType::EndorsedX509Cert)?;
+    let endorsed_obj_type = match format {
+        CertFormat::X509 => ObjType::EndorsedX509Cert,
+        CertFormat::Cwt => ObjType::EndorsedCwtCert,
+    };
+    let obj_header = perso_tlv_lib::make_obj_header(total_size, endorsed_obj_type)?;
     let cert_wrapper_header =
         perso_tlv_lib::make_cert_wrapper_header(cert.len(), ref_cert.cert_name)?;
     output.try_extend_from_slice(&obj_header.to_be_bytes())?;
@@ -301,9 +318,14 @@ fn provision_certificates(
     cert_endorsement_key_wrapper: KeyWrapper,
     perso_certgen_inputs: &ManufCertgenInputs,
     timeout: Duration,
-    ca_certificate: PathBuf,
+    ca_store_path: PathBuf,
+    cert_validator: &CertValidatorBackend,
     spi_console: &SpiConsoleDevice,
 ) -> Result<()> {
+    // Load the active CA and denylist from the signed, rollback-protected authority store
+    // instead of trusting a bare certificate file.
+    let ca_store = CaStore::load(&ca_store_path)?;
+
     // Send attestation TCB measurements for generating DICE certificates.
     let _ = UartConsole::wait_for(spi_console, r"Waiting for certificate inputs ...", timeout)?;
     perso_certgen_inputs.send(spi_console)?;
@@ -322,12 +344,36 @@ fn provision_certificates(
             log::info!("Using Cloud KMS key for cert endorsement");
             CertEndorsementKey::CkmsKey(key_id)
         }
+        KeyWrapper::Pkcs11Key {
+            module,
+            slot_or_token_label,
+            key_label,
+            pin,
+        } => {
+            log::info!("Using PKCS#11 token key for cert endorsement");
+            CertEndorsementKey::Pkcs11Key {
+                module,
+                slot_or_token_label,
+                key_label,
+                pin,
+            }
+        }
     };
-
-    // Extract certificate byte vectors, endorse TBS certs, and ensure they parse with OpenSSL.
-    // During the process, both:
+    // Opens (and, for a PKCS#11 token key, logs into) the CWT/COSE signing backend once for
+    // the whole run, so it can be reused across every CWT cert below.
+    //
+    // NOTE: this only covers the CBOR/CWT path. `parse_and_endorse_x509_cert` below manages
+    // its own PKCS#11 session internally (in `cert_lib`, outside this crate), so a run that
+    // endorses PKCS#11-backed X.509 certs still opens/logs into the token per signature, and
+    // a run that mixes X.509 and CWT certs under one PKCS#11 key opens two independent
+    // sessions instead of sharing this one. Unifying that requires `cert_lib` to accept a
+    // pre-opened session the way `CwtSigner` does; tracked as a follow-up there.
+    let cwt_signer = CwtSigner::new(&key)?;
+
+    // Extract certificate byte vectors, endorse TBS certs, and ensure they parse with the
+    // configured cert validation backend. During the process:
     //   1. prepare a UJSON payload of endorsed certs to send back to the device,
-    //   2. collect the certs that were endorsed to verify their endorsement signatures with OpenSSL, and
+    //   2. collect the certs that were endorsed to verify their endorsement signatures, and
     //   3. hash all certs to check the integrity of what gets written back to the device.
     let mut cert_hasher = Sha256::new();
     let mut start: usize = 0;
@@ -344,8 +390,9 @@ fn provision_certificates(
             bail!("Perso blob overflow!");
         }
         start += obj_header_size;
-        match header.obj_type {
-            ObjType::EndorsedX509Cert | ObjType::UnendorsedX509Cert => (),
+        let format = match header.obj_type {
+            ObjType::EndorsedX509Cert | ObjType::UnendorsedX509Cert => CertFormat::X509,
+            ObjType::EndorsedCwtCert | ObjType::UnendorsedCwtCert => CertFormat::Cwt,
             ObjType::DevSeed => {
                 let dev_seed_size = header.obj_size - obj_header_size;
                 let seeds = &perso_blob.body[start..start + dev_seed_size];
@@ -354,34 +401,60 @@ fn provision_certificates(
                 start += dev_seed_size;
                 continue;
             }
-        }
+        };
+        let needs_endorsement =
+            matches!(header.obj_type, ObjType::UnendorsedX509Cert | ObjType::UnendorsedCwtCert);
 
         // The next object is a cert, let's retrieve its properties (name, needs
         // endorsement, etc.)
         let cert = get_cert(&perso_blob.body[start..])?;
         start += cert.wrapped_size;
 
-        let cert_bytes = if header.obj_type == ObjType::UnendorsedX509Cert {
-            // Endorse the cert and updates its size.
-            let cert_bytes = parse_and_endorse_x509_cert(cert.cert_body.clone(), &key)?;
+        let cert_bytes = if needs_endorsement {
+            // Endorse the cert (X.509 or CBOR/CWT, per the object header's format) and
+            // update its size.
+            let cert_bytes = match format {
+                CertFormat::X509 => parse_and_endorse_x509_cert(cert.cert_body.clone(), &key)?,
+                CertFormat::Cwt => cwt_signer.parse_and_endorse_cwt_cert(cert.cert_body.clone())?,
+            };
 
-            // Prepare a collection of certs whose endorsements should be checked with OpenSSL.
+            // Prepare a collection of certs whose endorsements should be checked.
             host_endorsed_certs.push(HostEndorsedCert {
-                format: CertFormat::X509,
+                format,
                 bytes: cert_bytes.clone(),
                 ignore_critical: if cert.cert_name == "UDS" { true } else { false },
             });
 
             // Prepare the UJSON data payloads that will be sent back to the device.
-            push_endorsed_cert(&cert_bytes, &cert, &mut endorsed_cert_concat)?;
+            push_endorsed_cert(&cert_bytes, &cert, format, &mut endorsed_cert_concat)?;
             num_host_endorsed_certs += 1;
             cert_bytes
         } else {
             cert.cert_body
         };
-        // Ensure all certs parse with OpenSSL (even those that where endorsed on device).
+        // Reject any endorsed or on-device cert whose hash has been revoked, before spending
+        // any more effort validating it.
+        let cert_hash: [u8; 32] = Sha256::digest(&cert_bytes).into();
+        if ca_store.is_denylisted(&cert_hash) {
+            bail!(
+                "{} cert (hash {}) is present in the CA denylist (dbx)",
+                cert.cert_name,
+                hex::encode(cert_hash)
+            );
+        }
+        // Ensure all certs parse (even those that were endorsed on device): X.509 certs get a
+        // structural check with the configured cert validation backend either way. CBOR/CWT
+        // certs we just host-endorsed above get their COSE_Sign1 signature checked against
+        // `key`; CWT certs already endorsed on-device by a lower DICE layer's own key only
+        // get a structural COSE_Sign1 shape check, since `key` did not sign them.
         log::info!("{} Cert: {}", cert.cert_name, hex::encode(&cert_bytes));
-        let _ = parse_certificate(&cert_bytes)?;
+        match format {
+            CertFormat::X509 => {
+                let _ = cert_validator.parse(&cert_bytes)?;
+            }
+            CertFormat::Cwt if needs_endorsement => cwt_signer.verify_cwt_cert(&cert_bytes)?,
+            CertFormat::Cwt => check_cose_sign1_shape(&cert_bytes)?,
+        }
         // Push the cert into the hasher so we can ensure the certs written to the device's flash
         // info pages match those verified on the host.
         cert_hasher.update(cert_bytes);
@@ -422,9 +495,20 @@ fn provision_certificates(
         )
     }
 
-    // Validate the certificate endorsements with OpenSSL.
-    if !host_endorsed_certs.is_empty() {
-        validate_certs_chain(ca_certificate.to_str().unwrap(), &host_endorsed_certs)?;
+    // Validate the X.509 certificate endorsements with the configured cert validation
+    // backend, against the CA currently active in the authority store. CWT endorsements are
+    // not X.509 and are already checked via `cwt_signer.verify_cwt_cert` above, so they are
+    // excluded here rather than handed to an X.509 chain validator.
+    let host_endorsed_x509_certs: Vec<HostEndorsedCert> = host_endorsed_certs
+        .into_iter()
+        .filter(|cert| cert.format == CertFormat::X509)
+        .collect();
+    if !host_endorsed_x509_certs.is_empty() {
+        let ca_certificate_path = write_temp_ca_certificate(ca_store.active_ca_certificate())?;
+        cert_validator.validate_chain(
+            ca_certificate_path.to_str().unwrap(),
+            &host_endorsed_x509_certs,
+        )?;
     }
 
     Ok(())
@@ -437,7 +521,8 @@ pub fn run_ft_personalize(
     cert_endorsement_key_wrapper: KeyWrapper,
     perso_certgen_inputs: &ManufCertgenInputs,
     timeout: Duration,
-    ca_certificate: PathBuf,
+    ca_store_path: PathBuf,
+    cert_validator: &CertValidatorBackend,
     rma_unlock_token_hash: &ArrayVec<u32, 4>,
     spi_console: &SpiConsoleDevice,
     second_bootstrap: PathBuf,
@@ -453,7 +538,8 @@ pub fn run_ft_personalize(
         cert_endorsement_key_wrapper,
         perso_certgen_inputs,
         timeout,
-        ca_certificate,
+        ca_store_path,
+        cert_validator,
         spi_console,
     )?;
 
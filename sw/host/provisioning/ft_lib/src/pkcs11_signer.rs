@@ -0,0 +1,82 @@
+// Copyright lowRISC contributors (OpenTitan project).
+// Licensed under the Apache License, Version 2.0, see LICENSE for details.
+// SPDX-License-Identifier: Apache-2.0
+
+// Thin wrapper around a PKCS#11 module for ECDSA P-256 signing, so the certificate
+// endorsement key can live in a hardware security module instead of on disk.
+
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use cryptoki::context::{CInitializeArgs, Pkcs11};
+use cryptoki::mechanism::Mechanism;
+use cryptoki::object::{Attribute, KeyType, ObjectClass};
+use cryptoki::session::{Session, UserType};
+use cryptoki::types::AuthPin;
+use sha2::{Digest, Sha256};
+
+// A PKCS#11 session opened and logged in once, then reused (by `CwtSigner`) for every CWT
+// signature produced during a provisioning run. Re-initializing the module and logging in
+// again for every TBS cert (there may be several per run) is needless round-tripping with
+// the HSM and risks `CKR_CRYPTOKI_ALREADY_INITIALIZED` / re-login errors on real hardware.
+// The X.509 endorsement path does not use this session.
+pub struct Pkcs11Session {
+    session: Session,
+    key_label: String,
+}
+
+impl Pkcs11Session {
+    pub fn open(
+        module: &Path,
+        slot_or_token_label: &str,
+        key_label: &str,
+        pin: Option<&str>,
+    ) -> Result<Self> {
+        let pkcs11 = Pkcs11::new(module)
+            .with_context(|| format!("failed to load PKCS#11 module {}", module.display()))?;
+        pkcs11.initialize(CInitializeArgs::OsThreads)?;
+
+        let slot = pkcs11
+            .get_slots_with_token()?
+            .into_iter()
+            .find(|slot| {
+                pkcs11
+                    .get_token_info(*slot)
+                    .map(|info| info.label().trim_end() == slot_or_token_label)
+                    .unwrap_or(false)
+            })
+            .with_context(|| format!("no token found with label `{slot_or_token_label}`"))?;
+
+        let session = pkcs11.open_rw_session(slot)?;
+        if let Some(pin) = pin {
+            session.login(UserType::User, Some(&AuthPin::new(pin.to_string())))?;
+        }
+
+        Ok(Self {
+            session,
+            key_label: key_label.to_string(),
+        })
+    }
+
+    // Signs `message` with the session's private key. Performs the digest (SHA-256) on the
+    // host and asks the token to compute a raw `CKM_ECDSA` signature over it, since that is
+    // the signing mechanism DICE/X.509 endorsement needs and is near-universally supported by
+    // HSMs.
+    pub fn sign(&self, message: &[u8]) -> Result<Vec<u8>> {
+        let key = self
+            .session
+            .find_objects(&[
+                Attribute::Class(ObjectClass::PRIVATE_KEY),
+                Attribute::KeyType(KeyType::EC),
+                Attribute::Label(self.key_label.as_bytes().to_vec()),
+            ])?
+            .into_iter()
+            .next()
+            .with_context(|| format!("no private key found with label `{}`", self.key_label))?;
+
+        let digest = Sha256::digest(message);
+        let signature = self.session.sign(&Mechanism::Ecdsa, key, &digest)?;
+
+        Ok(signature)
+    }
+}